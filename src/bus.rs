@@ -0,0 +1,323 @@
+use std::cell::RefCell;
+
+/*
+`Bus` 把 CPU 和它背后的存储解耦开。
+CPU 不再直接持有一块内存数组，而是持有一个实现了 `Bus` 的类型，
+这样测试可以换成一块简单的 RAM，真正跑 ROM 时可以换成带 mapper 的卡带总线，
+以后接入 PPU/APU 也只需要在 `Bus` 的实现里做地址译码。
+*/
+
+pub trait Bus {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+
+    /*
+    6502 是小端序，读出的两个字节需要按 小端 拼成一个 u16
+    */
+    fn read_u16(&self, pos: u16) -> u16 {
+        let lo = self.read(pos) as u16;
+        let hi = self.read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.write(pos, lo);
+        self.write(pos.wrapping_add(1), hi);
+    }
+}
+
+/*
+最简单的 Bus 实现：一整块平坦的内存，没有镜像、没有设备译码。
+主要用于单元测试和之后接入真正的 NES 总线之前的过渡。
+*/
+pub struct SimpleBus {
+    memory: [u8; 0x10000],
+}
+
+impl SimpleBus {
+    pub fn new() -> Self {
+        SimpleBus {
+            memory: [0; 0x10000],
+        }
+    }
+}
+
+impl Default for SimpleBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for SimpleBus {
+    fn read(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+
+/*
+[0x2000, 0x401F] 里除了 PPU 寄存器之外还留给了别的设备（手柄、APU 等）。
+`MmioDevice` 让这些设备可以像真正的内存映射 IO 一样被挂到总线上：
+CPU 读写一段地址区间时，实际是在跟某个外设打交道，而不是读写 RAM。
+地址在送进 `read`/`write` 之前已经被换算成"相对这个设备起始地址的偏移"。
+*/
+pub trait MmioDevice {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+}
+
+struct MmioSlot {
+    start: u16,
+    end: u16,
+    device: Box<dyn MmioDevice>,
+}
+
+/*
+真正的 NES 总线：只有 2 KiB 的 CPU RAM，在 [0x0000, 0x1FFF] 里被镜像了 4 次，
+PPU 的 8 个寄存器也在 [0x2000, 0x3FFF] 里被不停镜像。
+地址要先译码到对应的"真实"地址，再去访问底层的存储。
+`devices` 用 RefCell 包一层，是因为 `Bus::read` 只拿 `&self`，
+但一次"读"可能需要消费掉设备内部的状态（比如读完就清空的按键缓冲区）。
+*/
+pub struct NesBus {
+    cpu_vram: [u8; 0x10000],
+    devices: RefCell<Vec<MmioSlot>>,
+}
+
+impl NesBus {
+    pub fn new() -> Self {
+        NesBus {
+            cpu_vram: [0; 0x10000],
+            devices: RefCell::new(Vec::new()),
+        }
+    }
+
+    // 把一个设备挂到 [start, end] 这段地址区间上，之后这段地址的读写都会先问它
+    pub fn register_device(&mut self, start: u16, end: u16, device: Box<dyn MmioDevice>) {
+        self.devices
+            .get_mut()
+            .push(MmioSlot { start, end, device });
+    }
+}
+
+impl Default for NesBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&self, addr: u16) -> u8 {
+        let mut devices = self.devices.borrow_mut();
+        if let Some(slot) = devices
+            .iter_mut()
+            .find(|slot| (slot.start..=slot.end).contains(&addr))
+        {
+            return slot.device.read(addr - slot.start);
+        }
+        drop(devices);
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                // 只有 11 位地址线接到了 2KiB 的 RAM 上，高位被忽略，于是产生镜像
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                // PPU 只暴露了 8 个寄存器，0x2008 往后是对这 8 个寄存器的镜像
+                let mirror_down_addr = addr & 0x2007;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            _ => self.cpu_vram[addr as usize],
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(slot) = self
+            .devices
+            .get_mut()
+            .iter_mut()
+            .find(|slot| (slot.start..=slot.end).contains(&addr))
+        {
+            slot.device.write(addr - slot.start, data);
+            return;
+        }
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0x2007;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            _ => self.cpu_vram[addr as usize] = data,
+        }
+    }
+}
+
+/*
+示例设备：LC-3 风格的键盘。占两个字节——状态寄存器（最高位表示"有新输入"）
+和数据寄存器（读一次就消费掉那个字节，并清空状态位）。
+*/
+pub struct Keyboard {
+    pending: Option<u8>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard { pending: None }
+    }
+
+    // 外部（键盘事件来源）把一个按键压进来，等 CPU 来轮询
+    pub fn type_byte(&mut self, byte: u8) {
+        self.pending = Some(byte);
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmioDevice for Keyboard {
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0 if self.pending.is_some() => 0b1000_0000,
+            0 => 0,
+            1 => self.pending.take().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {
+        // 键盘的寄存器都是只读的
+    }
+}
+
+/*
+示例设备：输出设备。任何写入都被当成一个字符，推进显示缓冲区，
+供调用方（比如测试或终端渲染器）之后读取。
+*/
+pub struct Display {
+    pub output: Vec<u8>,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display { output: Vec::new() }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmioDevice for Display {
+    fn read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        self.output.push(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ram_mirrors_are_shared() {
+        let mut bus = NesBus::new();
+        bus.write(0x0000, 0x42);
+        assert_eq!(bus.read(0x0800), 0x42);
+        assert_eq!(bus.read(0x1000), 0x42);
+        assert_eq!(bus.read(0x1800), 0x42);
+    }
+
+    #[test]
+    fn test_ppu_register_mirrors_are_shared() {
+        let mut bus = NesBus::new();
+        bus.write(0x2000, 0x7);
+        assert_eq!(bus.read(0x2008), 0x7);
+        assert_eq!(bus.read(0x3ff8), 0x7);
+    }
+
+    #[test]
+    fn test_irq_vector_is_addressable() {
+        // memory: [u8; 0xFFFF] 曾经少分配了一个字节，导致读 0xFFFE 这样的
+        // 中断向量会越界 panic；确保现在 0xFFFA..=0xFFFF 都能正常读写。
+        let mut bus = NesBus::new();
+        bus.write_u16(0xFFFE, 0xBEEF);
+        assert_eq!(bus.read_u16(0xFFFE), 0xBEEF);
+    }
+
+    #[test]
+    fn test_keyboard_device_reports_and_consumes_pending_input() {
+        let mut bus = NesBus::new();
+        let mut keyboard = Keyboard::new();
+        keyboard.type_byte(b'A');
+        bus.register_device(0x4016, 0x4017, Box::new(keyboard));
+
+        assert_eq!(bus.read(0x4016), 0b1000_0000); // KBSR: 有新输入
+        assert_eq!(bus.read(0x4017), b'A'); // KBDR: 消费掉这个字节
+        assert_eq!(bus.read(0x4016), 0); // 再读一次状态位：已经清空
+    }
+
+    #[test]
+    fn test_display_device_collects_written_bytes() {
+        let mut display = Display::new();
+        display.write(0, b'H');
+        display.write(0, b'I');
+        assert_eq!(display.output, vec![b'H', b'I']);
+    }
+
+    // 只在测试里用：把写入的最后一个字节原样读出来，用来证明总线确实把
+    // 读写都转发给了设备，而不是落到普通 RAM 里。
+    struct EchoDevice {
+        last_written: u8,
+    }
+
+    impl MmioDevice for EchoDevice {
+        fn read(&mut self, _addr: u16) -> u8 {
+            self.last_written
+        }
+
+        fn write(&mut self, _addr: u16, data: u8) {
+            self.last_written = data;
+        }
+    }
+
+    #[test]
+    fn test_device_dispatch_reads_and_writes_go_through_the_device() {
+        let mut bus = NesBus::new();
+        bus.register_device(0x4018, 0x4018, Box::new(EchoDevice { last_written: 0 }));
+
+        bus.write(0x4018, 0x77);
+        assert_eq!(bus.read(0x4018), 0x77);
+    }
+
+    #[test]
+    fn test_device_dispatch_falls_back_to_ram_outside_its_range() {
+        let mut bus = NesBus::new();
+        bus.register_device(0x4016, 0x4017, Box::new(Keyboard::new()));
+
+        bus.write(0x4020, 0x77);
+        assert_eq!(bus.read(0x4020), 0x77);
+    }
+}